@@ -0,0 +1,240 @@
+use std::io;
+use std::pin::Pin;
+use std::task::Poll;
+
+use bytes::BytesMut;
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Default initial capacity for a [`GrowableBuffer`].
+pub const DEFAULT_INITIAL_CAPACITY: usize = 8 * 1024;
+
+/// Default ceiling for a [`GrowableBuffer`] before [`GrowableBuffer::fill`]
+/// returns an error instead of growing further.
+pub const DEFAULT_MAX_CAPACITY: usize = 8 * 1024 * 1024;
+
+/// A [`BytesMut`]-backed read buffer that grows (doubling, up to
+/// `max_capacity`) when a read fills all of its spare capacity, and
+/// shrinks back toward `initial_capacity` once a read leaves slack.
+#[derive(Debug)]
+pub struct GrowableBuffer {
+    buf: BytesMut,
+    initial_capacity: usize,
+    max_capacity: usize,
+}
+
+impl GrowableBuffer {
+    /// Create a buffer starting at `initial_capacity` bytes that will
+    /// never hold more than `max_capacity` bytes.
+    pub fn new(initial_capacity: usize, max_capacity: usize) -> Self {
+        Self {
+            buf: BytesMut::with_capacity(initial_capacity),
+            initial_capacity,
+            max_capacity,
+        }
+    }
+
+    /// Bytes read so far that haven't been [`take`](GrowableBuffer::take)n out.
+    pub fn filled(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Take everything read so far, leaving the buffer empty.
+    pub fn take(&mut self) -> BytesMut {
+        self.buf.split()
+    }
+
+    /// Currently reserved capacity, including unfilled bytes.
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    /// Read once from `reader`, growing or shrinking the reserved
+    /// capacity based on how full the read left it.
+    ///
+    /// Returns `Ok(0)` on EOF, same as [`AsyncRead::poll_read`]. Errors
+    /// with [`io::ErrorKind::Other`] if the buffer already holds
+    /// `max_capacity` bytes without the caller having drained it.
+    pub async fn fill<R>(&mut self, reader: &mut R) -> io::Result<usize>
+    where
+        R: AsyncRead + Unpin,
+    {
+        if self.buf.len() >= self.max_capacity {
+            return Err(io::Error::other(
+                "GrowableBuffer exceeded max_capacity before a complete frame was found",
+            ));
+        }
+
+        if self.buf.capacity() == self.buf.len() {
+            let grow_by = self.buf.capacity().max(self.initial_capacity);
+            let grow_by = grow_by.min(self.max_capacity - self.buf.len());
+            self.buf.reserve(grow_by);
+        }
+
+        // `reserve` may allocate more than asked for, so cap the read
+        // itself to keep the buffer from holding more than `max_capacity`.
+        let spare = (self.buf.capacity() - self.buf.len()).min(self.max_capacity - self.buf.len());
+
+        let n = std::future::poll_fn(|cx| {
+            let mut read_buf = ReadBuf::uninit(&mut self.buf.spare_capacity_mut()[..spare]);
+
+            if let Err(e) = ready!(Pin::new(&mut *reader).poll_read(cx, &mut read_buf)) {
+                return Poll::Ready(Err(e));
+            }
+
+            Poll::Ready(Ok(read_buf.filled().len()))
+        })
+        .await?;
+
+        // safety: `poll_read` only reports bytes as filled in
+        // `read_buf` if it actually initialized them, and we grew
+        // `spare_capacity_mut` from `self.buf` above.
+        unsafe { self.buf.set_len(self.buf.len() + n) };
+
+        if n > 0 && n < spare / 2 {
+            self.shrink();
+        }
+
+        Ok(n)
+    }
+
+    /// Read from `reader` until `predicate` reports the buffer holds a
+    /// complete frame, or EOF is reached first.
+    pub async fn read_until<R>(
+        &mut self,
+        reader: &mut R,
+        mut predicate: impl FnMut(&[u8]) -> bool,
+    ) -> io::Result<()>
+    where
+        R: AsyncRead + Unpin,
+    {
+        while !predicate(self.filled()) {
+            if self.fill(reader).await? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "reader reached EOF before predicate was satisfied",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn shrink(&mut self) {
+        if self.buf.capacity() > self.initial_capacity && self.buf.len() <= self.initial_capacity {
+            let mut shrunk = BytesMut::with_capacity(self.initial_capacity);
+            shrunk.extend_from_slice(&self.buf);
+            self.buf = shrunk;
+        }
+    }
+}
+
+impl Default for GrowableBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_INITIAL_CAPACITY, DEFAULT_MAX_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::task::Context;
+
+    /// Reader that hands out at most `chunk_size` bytes of `data` per
+    /// `poll_read` call, then reports EOF.
+    struct ChunkReader {
+        data: Vec<u8>,
+        pos: usize,
+        chunk_size: usize,
+    }
+
+    impl ChunkReader {
+        fn new(data: Vec<u8>, chunk_size: usize) -> Self {
+            Self {
+                data,
+                pos: 0,
+                chunk_size,
+            }
+        }
+    }
+
+    impl AsyncRead for ChunkReader {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let me = self.get_mut();
+            let remaining = &me.data[me.pos..];
+            let n = remaining.len().min(buf.remaining()).min(me.chunk_size);
+
+            buf.put_slice(&remaining[..n]);
+            me.pos += n;
+
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fill_grows_after_filling_spare_capacity() {
+        let mut buf = GrowableBuffer::new(8, 1024);
+        let mut reader = ChunkReader::new(vec![b'a'; 8], 8);
+
+        assert_eq!(buf.fill(&mut reader).await.unwrap(), 8);
+        assert_eq!(buf.capacity(), 8);
+
+        // The previous read exactly filled the buffer, so this call
+        // grows the reserve before reading (the reader has nothing
+        // left, so it reports EOF).
+        assert_eq!(buf.fill(&mut reader).await.unwrap(), 0);
+        assert!(buf.capacity() > 8);
+    }
+
+    #[tokio::test]
+    async fn test_fill_errors_past_max_capacity() {
+        let mut buf = GrowableBuffer::new(8, 8);
+        let mut reader = ChunkReader::new(vec![b'a'; 8], 8);
+
+        assert_eq!(buf.fill(&mut reader).await.unwrap(), 8);
+
+        let err = buf.fill(&mut reader).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_shrink_collapses_oversized_capacity() {
+        let mut buf = GrowableBuffer::new(8, 1024);
+        buf.buf = BytesMut::with_capacity(256);
+        buf.buf.extend_from_slice(b"abcd");
+
+        buf.shrink();
+
+        assert_eq!(buf.capacity(), 8);
+        assert_eq!(buf.filled(), b"abcd");
+    }
+
+    #[tokio::test]
+    async fn test_read_until_predicate() {
+        let mut buf = GrowableBuffer::new(8, 1024);
+        let mut reader = ChunkReader::new(b"abc\nxyz".to_vec(), 3);
+
+        buf.read_until(&mut reader, |filled| filled.contains(&b'\n'))
+            .await
+            .unwrap();
+
+        assert!(buf.filled().contains(&b'\n'));
+    }
+
+    #[tokio::test]
+    async fn test_read_until_eof_before_predicate() {
+        let mut buf = GrowableBuffer::new(8, 1024);
+        let mut reader = ChunkReader::new(b"abc".to_vec(), 3);
+
+        let err = buf
+            .read_until(&mut reader, |filled| filled.contains(&b'\n'))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,61 @@
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::AsyncWrite;
+
+/// Write an entire set of `bufs` to `writer` using vectored writes,
+/// looping over short writes until every byte has been written.
+///
+/// A `0`-byte write is treated as [`io::ErrorKind::WriteZero`].
+pub async fn write_vectored_all<W>(writer: &mut W, mut bufs: &mut [IoSlice<'_>]) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    std::future::poll_fn(|cx| poll_write_vectored_all(Pin::new(&mut *writer), cx, &mut bufs)).await
+}
+
+fn poll_write_vectored_all<W>(
+    mut writer: Pin<&mut W>,
+    cx: &mut Context<'_>,
+    bufs: &mut &mut [IoSlice<'_>],
+) -> Poll<io::Result<()>>
+where
+    W: AsyncWrite,
+{
+    while !bufs.is_empty() {
+        let n = ready!(writer.as_mut().poll_write_vectored(cx, bufs))?;
+
+        if n == 0 {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "failed to write whole buffer",
+            )));
+        }
+
+        advance_slices(bufs, n);
+    }
+
+    Poll::Ready(Ok(()))
+}
+
+/// Advance a slice of [`IoSlice`]s by `n` bytes, dropping fully written
+/// slices and shortening the first partially written one, mirroring
+/// `futures`'s `write_all_vectored` helper.
+fn advance_slices(bufs: &mut &mut [IoSlice<'_>], mut n: usize) {
+    let mut to_remove = 0;
+
+    for buf in bufs.iter() {
+        if buf.len() > n {
+            break;
+        }
+
+        n -= buf.len();
+        to_remove += 1;
+    }
+
+    *bufs = &mut std::mem::take(bufs)[to_remove..];
+    if !bufs.is_empty() {
+        bufs[0].advance(n);
+    }
+}
@@ -0,0 +1,363 @@
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::task::JoinHandle;
+
+/// Size of the reusable buffer each blocking `read`/`write` call is
+/// handed.
+const BUF_SIZE: usize = 16 * 1024;
+
+/// The reusable buffer moved into and back out of a blocking task.
+struct Buf {
+    bytes: Vec<u8>,
+    pos: usize,
+    filled: usize,
+}
+
+impl Buf {
+    fn new() -> Self {
+        Self {
+            bytes: vec![0; BUF_SIZE],
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.filled
+    }
+
+    fn copy_to(&mut self, dst: &mut ReadBuf<'_>) {
+        let n = dst.remaining().min(self.filled - self.pos);
+        dst.put_slice(&self.bytes[self.pos..self.pos + n]);
+        self.pos += n;
+    }
+
+    fn set_write(&mut self, src: &[u8]) {
+        let n = src.len().min(self.bytes.len());
+        self.bytes[..n].copy_from_slice(&src[..n]);
+        self.pos = 0;
+        self.filled = n;
+    }
+}
+
+/// Which blocking call a write-direction `State::Busy` is running.
+///
+/// `poll_write` and `poll_flush` share one `State`, so a task spawned by
+/// one of them has to be tagged with which it is — otherwise a `flush`
+/// that finds the state `Busy` from a still-in-flight `write` (e.g. the
+/// `write` future was dropped after returning `Pending`) can't tell it
+/// isn't its own task completing, and would report that stale write's
+/// result as the flush's.
+enum WriteOp {
+    Write,
+    Flush,
+}
+
+/// One direction's (read or write) idle/in-flight bookkeeping.
+enum State {
+    Idle(Option<Buf>),
+    Busy(JoinHandle<(io::Result<usize>, Buf)>),
+}
+
+/// Write-direction state additionally tags `Busy` with [`WriteOp`].
+enum WriteState {
+    Idle(Option<Buf>),
+    Busy(WriteOp, JoinHandle<(io::Result<usize>, Buf)>),
+}
+
+/// Bridges a synchronous [`Read`]/[`Write`] type into
+/// [`AsyncRead`]/[`AsyncWrite`] by dispatching each call onto
+/// [`tokio::task::spawn_blocking`].
+///
+/// Reads and writes keep independent `State`, so e.g. `tokio::io::split`
+/// can drive both halves concurrently without one stealing the other's
+/// result; `inner` is behind a [`Mutex`] purely so the two blocking
+/// tasks can each borrow it from their own thread.
+pub struct Blocking<T> {
+    inner: Arc<Mutex<T>>,
+    read_state: State,
+    write_state: WriteState,
+    need_flush: bool,
+}
+
+impl<T> Blocking<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+            read_state: State::Idle(Some(Buf::new())),
+            write_state: WriteState::Idle(Some(Buf::new())),
+            need_flush: false,
+        }
+    }
+
+    /// Returns the inner synchronous value, or `None` if a `read`,
+    /// `write` or `flush` is still in flight on the blocking pool.
+    pub fn into_inner(self) -> Option<T> {
+        drop(self.read_state);
+        drop(self.write_state);
+
+        Arc::try_unwrap(self.inner)
+            .ok()
+            .map(|mutex| mutex.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+}
+
+impl<T: Read + Send + 'static> AsyncRead for Blocking<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        dst: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+
+        loop {
+            match &mut me.read_state {
+                State::Idle(idle) => {
+                    let mut buf = idle.take().expect("Blocking: read buffer lost after an error");
+
+                    if !buf.is_empty() {
+                        buf.copy_to(dst);
+                        *idle = Some(buf);
+                        return Poll::Ready(Ok(()));
+                    }
+
+                    let inner = Arc::clone(&me.inner);
+                    me.read_state = State::Busy(tokio::task::spawn_blocking(move || {
+                        let res = inner.lock().unwrap().read(&mut buf.bytes);
+                        if let Ok(n) = res {
+                            buf.pos = 0;
+                            buf.filled = n;
+                        }
+                        (res, buf)
+                    }));
+                }
+                State::Busy(handle) => {
+                    let (res, mut buf) =
+                        ready!(Pin::new(handle).poll(cx)).expect("blocking read task panicked");
+
+                    match res {
+                        Ok(_) => {
+                            buf.copy_to(dst);
+                            me.read_state = State::Idle(Some(buf));
+                            return Poll::Ready(Ok(()));
+                        }
+                        Err(e) => {
+                            me.read_state = State::Idle(Some(buf));
+                            return Poll::Ready(Err(e));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T: Write + Send + 'static> AsyncWrite for Blocking<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        src: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let me = self.get_mut();
+
+        loop {
+            match &mut me.write_state {
+                WriteState::Idle(idle) => {
+                    let mut buf = idle.take().expect("Blocking: write buffer lost after an error");
+                    buf.set_write(src);
+
+                    let inner = Arc::clone(&me.inner);
+                    me.write_state = WriteState::Busy(
+                        WriteOp::Write,
+                        tokio::task::spawn_blocking(move || {
+                            let res = inner.lock().unwrap().write(&buf.bytes[..buf.filled]);
+                            (res, buf)
+                        }),
+                    );
+                    me.need_flush = true;
+                }
+                WriteState::Busy(WriteOp::Write, handle) => {
+                    let (res, buf) =
+                        ready!(Pin::new(handle).poll(cx)).expect("blocking write task panicked");
+
+                    me.write_state = WriteState::Idle(Some(buf));
+                    return Poll::Ready(res);
+                }
+                // An earlier `flush` is still in flight (its future was
+                // dropped without being polled to completion); wait for
+                // it so that flush and this write stay ordered, but
+                // don't report its result as this write's.
+                WriteState::Busy(WriteOp::Flush, handle) => {
+                    let (res, buf) =
+                        ready!(Pin::new(handle).poll(cx)).expect("blocking flush task panicked");
+
+                    me.write_state = WriteState::Idle(Some(buf));
+                    if let Err(e) = res {
+                        return Poll::Ready(Err(e));
+                    }
+                }
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+
+        loop {
+            match &mut me.write_state {
+                WriteState::Idle(idle) => {
+                    if !me.need_flush {
+                        return Poll::Ready(Ok(()));
+                    }
+
+                    let buf = idle.take().expect("Blocking: write buffer lost after an error");
+                    let inner = Arc::clone(&me.inner);
+
+                    me.write_state = WriteState::Busy(
+                        WriteOp::Flush,
+                        tokio::task::spawn_blocking(move || {
+                            let res = inner.lock().unwrap().flush().map(|()| 0);
+                            (res, buf)
+                        }),
+                    );
+                }
+                // A previous `write` is still in flight (its future was
+                // dropped after getting `Pending`, so nobody has polled
+                // it to completion); wait for it before issuing the real
+                // flush, rather than reporting its result as the flush's.
+                WriteState::Busy(WriteOp::Write, handle) => {
+                    let (res, buf) =
+                        ready!(Pin::new(handle).poll(cx)).expect("blocking write task panicked");
+
+                    me.write_state = WriteState::Idle(Some(buf));
+                    if let Err(e) = res {
+                        return Poll::Ready(Err(e));
+                    }
+                }
+                WriteState::Busy(WriteOp::Flush, handle) => {
+                    let (res, buf) =
+                        ready!(Pin::new(handle).poll(cx)).expect("blocking flush task panicked");
+
+                    me.write_state = WriteState::Idle(Some(buf));
+
+                    return match res {
+                        Ok(_) => {
+                            me.need_flush = false;
+                            Poll::Ready(Ok(()))
+                        }
+                        Err(e) => Poll::Ready(Err(e)),
+                    };
+                }
+            }
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn test_read() {
+        let mut blocking = Blocking::new(Cursor::new(b"Hello, world!".to_vec()));
+
+        let mut out = Vec::new();
+        blocking.read_to_end(&mut out).await.unwrap();
+
+        assert_eq!(out, b"Hello, world!");
+    }
+
+    #[tokio::test]
+    async fn test_write_and_flush() {
+        let mut blocking = Blocking::new(Vec::new());
+
+        blocking.write_all(b"Hello, world!").await.unwrap();
+        blocking.flush().await.unwrap();
+
+        assert_eq!(
+            blocking.into_inner().unwrap(),
+            b"Hello, world!".to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_read_and_write_do_not_cross_wires() {
+        // `Cursor<Vec<u8>>` is both `Read` and `Write`; split it the way
+        // a real duplex user would, so both `State`s are driven
+        // concurrently and must not hand each other's result back.
+        let blocking = Blocking::new(Cursor::new(b"read me".to_vec()));
+        let (mut rd, mut wr) = tokio::io::split(blocking);
+
+        let (read_result, write_result) = tokio::join!(
+            async {
+                let mut out = vec![0u8; 7];
+                rd.read_exact(&mut out).await.unwrap();
+                out
+            },
+            wr.write_all(b"ignored"),
+        );
+
+        assert_eq!(read_result, b"read me");
+        write_result.unwrap();
+    }
+
+    /// `Write` whose `write` blocks until the test releases it, so a
+    /// `poll_write` can be left `Busy` indefinitely, and whose `flush`
+    /// records whether it actually ran.
+    struct BlockingWriter {
+        release: mpsc::Receiver<()>,
+        flushed: Arc<AtomicBool>,
+    }
+
+    impl Write for BlockingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.release.recv().unwrap();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flush_does_not_no_op_on_abandoned_write() {
+        let (release_tx, release_rx) = mpsc::channel();
+        let flushed = Arc::new(AtomicBool::new(false));
+
+        let mut blocking = Blocking::new(BlockingWriter {
+            release: release_rx,
+            flushed: Arc::clone(&flushed),
+        });
+
+        // Dispatch a write and abandon it before it completes (as
+        // `tokio::select!`/a timeout racing the write future would),
+        // leaving `write_state` `Busy` with nobody left to poll it.
+        timeout(Duration::from_millis(0), blocking.write_all(b"x"))
+            .await
+            .unwrap_err();
+
+        release_tx.send(()).unwrap();
+
+        blocking.flush().await.unwrap();
+        assert!(flushed.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,51 @@
+use std::io::{IoSlice, IoSliceMut};
+
+/// Extension trait to advance an [`IoSlice`] by `n` bytes in place.
+///
+/// This is a stable-Rust substitute for the unstable
+/// [`IoSlice::advance`](https://doc.rust-lang.org/std/io/struct.IoSlice.html#method.advance).
+pub trait IoSliceExt<'a> {
+    /// Advance the slice by `n` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is larger than the length of the slice.
+    fn advance(&mut self, n: usize);
+}
+
+impl<'a> IoSliceExt<'a> for IoSlice<'a> {
+    fn advance(&mut self, n: usize) {
+        let bytes = &self[n..];
+
+        // safety: `bytes` borrows from the same `'a` buffer `self` was
+        // created from, just starting further into it, so re-wrapping
+        // it as an `IoSlice<'a>` does not extend its actual lifetime.
+        *self = IoSlice::new(unsafe { std::mem::transmute::<&[u8], &'a [u8]>(bytes) });
+    }
+}
+
+/// Extension trait to advance an [`IoSliceMut`] by `n` bytes in place.
+///
+/// This is a stable-Rust substitute for the unstable
+/// [`IoSliceMut::advance`](https://doc.rust-lang.org/std/io/struct.IoSliceMut.html#method.advance).
+pub trait IoSliceMutExt<'a> {
+    /// Advance the slice by `n` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is larger than the length of the slice.
+    fn advance(&mut self, n: usize);
+}
+
+impl<'a> IoSliceMutExt<'a> for IoSliceMut<'a> {
+    fn advance(&mut self, n: usize) {
+        let mut owned = std::mem::replace(self, IoSliceMut::new(&mut []));
+        let bytes = &mut owned[n..];
+
+        // safety: `bytes` borrows from the same `'a` buffer `self` was
+        // created from, just starting further into it, so re-wrapping
+        // it as an `IoSliceMut<'a>` does not extend its actual lifetime.
+        let bytes: &'a mut [u8] = unsafe { std::mem::transmute::<&mut [u8], &'a mut [u8]>(bytes) };
+        *self = IoSliceMut::new(bytes);
+    }
+}
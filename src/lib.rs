@@ -21,6 +21,10 @@ mod io_slice_ext;
 #[cfg_attr(docsrs, doc(cfg(feature = "mpsc")))]
 pub mod queue;
 
+#[cfg(feature = "blocking")]
+#[cfg_attr(docsrs, doc(cfg(feature = "blocking")))]
+pub mod blocking;
+
 pub use async_read_utility::*;
 pub use async_write_utility::write_vectored_all;
 pub use io_slice_ext::{IoSliceExt, IoSliceMutExt};
@@ -1,31 +1,62 @@
 use std::cell::UnsafeCell;
-use std::io::IoSlice;
+use std::future::Future;
+use std::io::{self, IoSlice};
 use std::mem::{transmute, MaybeUninit};
 use std::num::NonZeroUsize;
+use std::pin::Pin;
 use std::sync::atomic::{AtomicU16, Ordering};
+use std::task::{Context, Poll};
 
 use bytes::{Buf, Bytes};
+use crossbeam_utils::CachePadded;
 use parking_lot::{Mutex, MutexGuard};
+use tokio::io::AsyncWrite;
+
+/// Linux caps the number of `iovec`s a single `writev`/`readv` call will
+/// look at; anything past this is silently ignored by the kernel, so
+/// `get_buffers` must never hand out more slices than this in one batch.
+const IOV_MAX: usize = 1024;
+
+/// One slot in `MpScBytesQueue::bytes_queue`.
+///
+/// `stamp` publishes the slot: a producer writes `bytes` then stores
+/// `stamp = position + 1`, so the consumer only needs to compare
+/// `stamp` against the position it expects instead of waiting on a
+/// global "tail done" counter.
+///
+/// Deliberately *not* cache-line padded: `get_buffers` scans
+/// `bytes_queue` linearly, so slots should pack several stamps per
+/// cache line rather than each claiming one to itself.
+#[derive(Debug)]
+struct Slot {
+    bytes: UnsafeCell<Bytes>,
+    stamp: AtomicU16,
+}
 
 #[derive(Debug)]
 pub struct MpScBytesQueue {
-    bytes_queue: Box<[UnsafeCell<Bytes>]>,
+    bytes_queue: Box<[Slot]>,
     io_slice_buf: Mutex<Box<[MaybeUninit<IoSlice<'static>>]>>,
 
-    /// The head to read from
-    head: AtomicU16,
+    /// The head to read from.
+    ///
+    /// Unlike an array index, this counts monotonically (wrapping at
+    /// `u16::MAX`) instead of wrapping at `capacity()`, so that the
+    /// same array slot can be distinguished across successive laps.
+    head: CachePadded<AtomicU16>,
 
-    /// The tail to write to.
-    tail_pending: AtomicU16,
+    /// The tail to write to, counted the same way as `head`.
+    tail: CachePadded<AtomicU16>,
 
-    /// The tail where writing is done.
-    tail_done: AtomicU16,
+    /// Number of entries free.
+    free: CachePadded<AtomicU16>,
 
-    /// Number of entries free
-    free: AtomicU16,
+    /// Number of entries occupied.
+    len: CachePadded<AtomicU16>,
 
-    /// Number of entries occupied
-    len: AtomicU16,
+    /// Upper bound on the number of `IoSlice`s `get_buffers` will
+    /// materialize in one batch, e.g. to stay under `IOV_MAX`.
+    max_io_slices: usize,
 }
 
 unsafe impl Send for MpScBytesQueue {}
@@ -33,18 +64,40 @@ unsafe impl Sync for MpScBytesQueue {}
 
 impl MpScBytesQueue {
     pub fn new(cap: u16) -> Self {
-        let bytes_queue: Vec<_> = (0..cap).map(|_| UnsafeCell::new(Bytes::new())).collect();
-        let io_slice_buf: Vec<_> = (0..(cap as usize)).map(|_| MaybeUninit::uninit()).collect();
+        Self::with_max_io_slices(cap, IOV_MAX)
+    }
+
+    /// Like [`MpScBytesQueue::new`], but overrides the default cap of
+    /// [`IOV_MAX`](https://man7.org/linux/man-pages/man2/writev.2.html)
+    /// on the number of `IoSlice`s `get_buffers` returns in one batch.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_io_slices == 0`: `get_buffers` would then never
+    /// hand out any slices, so the queue could never be drained.
+    pub fn with_max_io_slices(cap: u16, max_io_slices: usize) -> Self {
+        assert!(max_io_slices > 0, "max_io_slices must be greater than 0");
+
+        let bytes_queue: Vec<_> = (0..cap)
+            .map(|i| Slot {
+                bytes: UnsafeCell::new(Bytes::new()),
+                stamp: AtomicU16::new(i),
+            })
+            .collect();
+
+        let max_io_slices = max_io_slices.min(cap as usize);
+        let io_slice_buf: Vec<_> = (0..max_io_slices).map(|_| MaybeUninit::uninit()).collect();
 
         Self {
             bytes_queue: bytes_queue.into_boxed_slice(),
             io_slice_buf: Mutex::new(io_slice_buf.into_boxed_slice()),
 
-            head: AtomicU16::new(0),
-            tail_pending: AtomicU16::new(0),
-            tail_done: AtomicU16::new(0),
-            free: AtomicU16::new(cap),
-            len: AtomicU16::new(0),
+            head: CachePadded::new(AtomicU16::new(0)),
+            tail: CachePadded::new(AtomicU16::new(0)),
+            free: CachePadded::new(AtomicU16::new(cap)),
+            len: CachePadded::new(AtomicU16::new(0)),
+
+            max_io_slices,
         }
     }
 
@@ -82,39 +135,43 @@ impl MpScBytesQueue {
             }
         }
 
-        // Update tail_pending
-        let mut tail_pending = self.tail_pending.load(Ordering::Relaxed);
-        let mut new_tail_pending;
+        // Reserve a contiguous range `[tail, tail + slice_len)`.
+        //
+        // `free` above already guarantees exclusive ownership of these
+        // slots until they are published below, so no other producer
+        // can be writing to the same range concurrently.
+        let mut tail = self.tail.load(Ordering::Relaxed);
         loop {
-            new_tail_pending = u16::overflowing_add(tail_pending, slice_len).0 % (queue_cap as u16);
+            let new_tail = tail.wrapping_add(slice_len);
 
-            match self.tail_pending.compare_exchange_weak(
-                tail_pending,
-                new_tail_pending,
+            match self.tail.compare_exchange_weak(
+                tail,
+                new_tail,
                 Ordering::Relaxed,
                 Ordering::Relaxed,
             ) {
                 Ok(_) => break,
-                Err(new_value) => tail_pending = new_value,
+                Err(new_value) => tail = new_value,
             }
         }
 
-        // Acquire load to wait for writes to complete
+        // Acquire load to synchronize with the consumer's `head.store`
+        // (queue.rs `advance`): without this, nothing establishes
+        // happens-before between a consumer resetting a slot's `Bytes`
+        // to reuse it and this producer writing into that same slot
+        // once the queue has wrapped around.
         self.head.load(Ordering::Acquire);
 
-        // Write the value
-        let mut i = tail_pending as usize;
-        for bytes in slice {
-            let ptr = self.bytes_queue[i].get();
-            unsafe { ptr.replace(bytes.clone()) };
+        // Write the value and publish each slot individually, so a slow
+        // producer only blocks the consumer from observing its own
+        // slots instead of every slot reserved after it.
+        for k in 0..slice_len {
+            let pos = tail.wrapping_add(k);
+            let slot = &self.bytes_queue[(pos as usize) % queue_cap];
 
-            i = (i + 1) % queue_cap;
+            unsafe { slot.bytes.get().replace(slice[k as usize].clone()) };
+            slot.stamp.store(pos.wrapping_add(1), Ordering::Release);
         }
-        debug_assert_eq!(i, new_tail_pending as usize);
-
-        // Update tail_done to new_tail_pending with SeqCst
-        while self.tail_done.load(Ordering::Relaxed) != tail_pending {}
-        self.tail_done.store(new_tail_pending, Ordering::SeqCst);
 
         self.len.fetch_add(slice_len, Ordering::Relaxed);
 
@@ -126,7 +183,7 @@ impl MpScBytesQueue {
     /// Return `None` if there isn't any buffer to flush or another
     /// thread is doing the flushing.
     pub fn get_buffers(&self) -> Option<Buffers<'_>> {
-        let queue_cap = self.bytes_queue.len() as u16;
+        let queue_cap = self.bytes_queue.len();
 
         let mut guard = self.io_slice_buf.try_lock()?;
 
@@ -136,29 +193,102 @@ impl MpScBytesQueue {
         }
 
         let head = self.head.load(Ordering::Relaxed);
-        // SeqCst load to wait for writes to complete
-        let tail = self.tail_done.load(Ordering::SeqCst);
 
         let pointer = (&mut **guard) as *mut [MaybeUninit<IoSlice>];
         let uninit_slice: &mut [MaybeUninit<IoSlice>] = unsafe { &mut *pointer };
 
-        let mut j = head as usize;
-        for i in 0..(len as usize) {
-            uninit_slice[i].write(IoSlice::new(unsafe { &**self.bytes_queue[j].get() }));
-            j = usize::overflowing_add(j, 1).0 % (queue_cap as usize);
+        // Scan forward from `head`, stopping at the first slot whose
+        // stamp shows it hasn't been published by its producer yet, or
+        // once `max_io_slices` have been collected. A queue larger than
+        // `max_io_slices` is drained by calling `get_buffers` again
+        // after `advance` has consumed this batch, continuing from the
+        // new `head`.
+        let batch = (len as usize).min(self.max_io_slices);
+        let mut ready = 0;
+        while ready < batch {
+            let pos = head.wrapping_add(ready as u16);
+            let slot = &self.bytes_queue[(pos as usize) % queue_cap];
+
+            if slot.stamp.load(Ordering::Acquire) != pos.wrapping_add(1) {
+                break;
+            }
+
+            uninit_slice[ready].write(IoSlice::new(unsafe { &*slot.bytes.get() }));
+            ready += 1;
         }
 
-        debug_assert_eq!(j, tail as usize);
+        if ready == 0 {
+            return None;
+        }
 
         Some(Buffers {
             queue: self,
             guard,
             io_slice_start: 0,
-            io_slice_end: len,
+            io_slice_end: ready as u16,
             head,
-            tail,
+            tail: head.wrapping_add(ready as u16),
         })
     }
+
+    /// Drain the queue into `writer` using vectored writes, looping
+    /// over [`get_buffers`](MpScBytesQueue::get_buffers)/`advance`
+    /// batches until `get_buffers` returns `None`: the queue is empty,
+    /// or the slot at `head` has not been published by its producer yet,
+    /// in which case entries may still be queued when this returns.
+    ///
+    /// A `0`-byte write is treated as [`io::ErrorKind::WriteZero`].
+    ///
+    /// Only one caller should be draining the queue via `flush_to` (or
+    /// `get_buffers`) at a time; a concurrent caller simply observes an
+    /// empty batch and returns early.
+    pub async fn flush_to<'a, W>(&'a self, writer: &'a mut W) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        FlushTo {
+            queue: self,
+            writer,
+        }
+        .await
+    }
+}
+
+struct FlushTo<'a, W> {
+    queue: &'a MpScBytesQueue,
+    writer: &'a mut W,
+}
+
+impl<W: AsyncWrite + Unpin> Future for FlushTo<'_, W> {
+    type Output = io::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            let mut buffers = match this.queue.get_buffers() {
+                Some(buffers) => buffers,
+                None => return Poll::Ready(Ok(())),
+            };
+
+            loop {
+                let n = ready!(
+                    Pin::new(&mut *this.writer).poll_write_vectored(cx, buffers.get_io_slices())
+                )?;
+
+                if n == 0 {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    )));
+                }
+
+                if !buffers.advance(NonZeroUsize::new(n).unwrap()) {
+                    break;
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -183,7 +313,8 @@ impl Buffers<'_> {
     }
 
     unsafe fn get_first_bytes(&mut self) -> &mut Bytes {
-        &mut *self.queue.bytes_queue[self.head as usize].get()
+        let queue_cap = self.queue.bytes_queue.len();
+        &mut *self.queue.bytes_queue[(self.head as usize) % queue_cap].bytes.get()
     }
 
     /// * `n` - bytes successfully written.
@@ -197,7 +328,6 @@ impl Buffers<'_> {
         let mut n = n.get();
 
         let queue = self.queue;
-        let queue_cap = queue.capacity() as u16;
 
         let pointer = (&mut **self.guard) as *mut [MaybeUninit<IoSlice>];
         let uninit_slice: &mut [MaybeUninit<IoSlice>] = unsafe { &mut *pointer };
@@ -221,7 +351,7 @@ impl Buffers<'_> {
 
             // Decrement len and Increment head
             queue.len.fetch_sub(1, Ordering::Relaxed);
-            self.head = u16::overflowing_add(self.head, 1).0 % queue_cap;
+            self.head = self.head.wrapping_add(1);
             queue.head.store(self.head, Ordering::Release);
 
             // Increment free
@@ -250,9 +380,13 @@ mod tests {
     use super::MpScBytesQueue;
 
     use bytes::Bytes;
+    use std::io;
     use std::num::NonZeroUsize;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
 
     use rayon::prelude::*;
+    use tokio::io::AsyncWrite;
 
     #[test]
     fn test_seq() {
@@ -331,4 +465,92 @@ mod tests {
             }
         });
     }
+
+    #[test]
+    #[should_panic(expected = "max_io_slices must be greater than 0")]
+    fn test_with_max_io_slices_rejects_zero() {
+        MpScBytesQueue::with_max_io_slices(10, 0);
+    }
+
+    #[test]
+    fn test_get_buffers_capped_and_resumable() {
+        let bytes = Bytes::from_static(b"x");
+
+        let queue = MpScBytesQueue::with_max_io_slices(10, 4);
+
+        for _ in 0..10 {
+            queue.push(std::slice::from_ref(&bytes)).unwrap();
+        }
+
+        // First batch is capped at `max_io_slices`, not `capacity()`.
+        let mut buffers = queue.get_buffers().unwrap();
+        assert_eq!(buffers.get_io_slices().len(), 4);
+        assert!(!buffers.advance(NonZeroUsize::new(4).unwrap()));
+        drop(buffers);
+
+        // The second batch resumes from the new `head`.
+        let mut buffers = queue.get_buffers().unwrap();
+        assert_eq!(buffers.get_io_slices().len(), 4);
+        assert!(!buffers.advance(NonZeroUsize::new(4).unwrap()));
+        drop(buffers);
+
+        let mut buffers = queue.get_buffers().unwrap();
+        assert_eq!(buffers.get_io_slices().len(), 2);
+        assert!(!buffers.advance(NonZeroUsize::new(2).unwrap()));
+        drop(buffers);
+
+        assert!(queue.get_buffers().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_flush_to() {
+        let bytes = Bytes::from_static(b"Hello, world!");
+
+        let queue = MpScBytesQueue::new(4);
+        queue.push(&[bytes.clone(), bytes.clone()]).unwrap();
+        queue.push(&[bytes.clone(), bytes.clone()]).unwrap();
+
+        let mut writer = Vec::new();
+        queue.flush_to(&mut writer).await.unwrap();
+
+        let mut expected = Vec::new();
+        for _ in 0..4 {
+            expected.extend_from_slice(&bytes);
+        }
+        assert_eq!(writer, expected);
+        assert!(queue.get_buffers().is_none());
+    }
+
+    /// Writer that always reports writing zero bytes, to exercise
+    /// `flush_to`'s `WriteZero` path.
+    struct ZeroWriter;
+
+    impl AsyncWrite for ZeroWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(Ok(0))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flush_to_zero_write_is_write_zero_error() {
+        let bytes = Bytes::from_static(b"x");
+
+        let queue = MpScBytesQueue::new(4);
+        queue.push(std::slice::from_ref(&bytes)).unwrap();
+
+        let err = queue.flush_to(&mut ZeroWriter).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+    }
 }